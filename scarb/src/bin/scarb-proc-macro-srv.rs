@@ -0,0 +1,25 @@
+//! Out-of-process host for a single procedural macro library.
+//!
+//! Spawned by Scarb (see `ProcMacroInstance::try_new`) when
+//! `SCARB_PROC_MACRO_SERVER` is set, so that a crash while expanding a macro takes
+//! down only this disposable process rather than the whole `scarb build`.
+//!
+//! Usage: `scarb-proc-macro-srv <path-to-macro-library>`
+
+use anyhow::{bail, Context, Result};
+use camino::Utf8PathBuf;
+use scarb::compiler::plugin::proc_macro::ffi::serve;
+
+fn main() -> Result<()> {
+    let mut args = std::env::args_os().skip(1);
+    let library_path = args
+        .next()
+        .context("expected path to the procedural macro library as the first argument")?;
+    if args.next().is_some() {
+        bail!("scarb-proc-macro-srv takes exactly one argument: the macro library path");
+    }
+    let library_path = Utf8PathBuf::from_path_buf(library_path.into())
+        .map_err(|path| anyhow::anyhow!("library path `{}` is not valid UTF-8", path.display()))?;
+
+    serve(library_path)
+}
@@ -1,5 +1,5 @@
 use crate::core::{Config, Package, PackageId};
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use cairo_lang_defs::patcher::PatchBuilder;
 use cairo_lang_macro::{AuxData, ProcMacroResult, TokenStream};
 use cairo_lang_macro_stable::{
@@ -9,9 +9,12 @@ use cairo_lang_syntax::node::db::SyntaxGroup;
 use cairo_lang_syntax::node::{ast, TypedSyntaxNode};
 use camino::Utf8PathBuf;
 use libloading::{Library, Symbol};
+use std::ffi::{CStr, CString};
 use std::fmt::Debug;
 
 use crate::compiler::plugin::proc_macro::compilation::SharedLibraryProvider;
+use crate::compiler::plugin::proc_macro::srv::ProcMacroServerConnection;
+use crate::compiler::plugin::proc_macro::validation::validate_library;
 use crate::compiler::plugin::proc_macro::ProcMacroAuxData;
 use cairo_lang_macro_stable::ffi::StableSlice;
 #[cfg(not(windows))]
@@ -33,13 +36,104 @@ impl FromItemAst for TokenStream {
     }
 }
 
+/// The three shapes a procedural macro can take, each receiving a different set of
+/// token streams to expand.
+///
+/// Mirrors the distinction rustc's proc-macro bridge and rust-analyzer's
+/// `proc-macro-srv` make between attribute, derive, and function-like macros.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum ProcMacroKind {
+    /// Receives the annotated item and the attribute's own argument token stream.
+    Attribute,
+    /// Receives the annotated item; the derive name is the macro name itself.
+    Derive,
+    /// Receives the call's argument token stream, written as `macro_name!(args)`.
+    Bang,
+}
+
+impl ProcMacroKind {
+    fn as_u8(self) -> u8 {
+        match self {
+            ProcMacroKind::Attribute => 0,
+            ProcMacroKind::Derive => 1,
+            ProcMacroKind::Bang => 2,
+        }
+    }
+
+    fn from_u8(value: u8) -> Result<Self> {
+        match value {
+            0 => Ok(ProcMacroKind::Attribute),
+            1 => Ok(ProcMacroKind::Derive),
+            2 => Ok(ProcMacroKind::Bang),
+            other => bail!("unknown procedural macro kind discriminant {other}"),
+        }
+    }
+}
+
+/// The token streams passed to [`ProcMacroInstance::generate_code`], shaped
+/// according to the macro's [`ProcMacroKind`].
+pub(crate) enum ExpandArgs {
+    Attribute {
+        item: TokenStream,
+        args: TokenStream,
+    },
+    Derive {
+        item: TokenStream,
+    },
+    Bang {
+        args: TokenStream,
+    },
+}
+
+impl ExpandArgs {
+    /// The [`ProcMacroKind`] this variant corresponds to. `generate_code` derives the
+    /// kind it sends across the FFI boundary from this instead of taking it as a
+    /// separate parameter, so a caller can't mismatch `kind` against the shape of the
+    /// token streams actually being sent.
+    fn kind(&self) -> ProcMacroKind {
+        match self {
+            ExpandArgs::Attribute { .. } => ProcMacroKind::Attribute,
+            ExpandArgs::Derive { .. } => ProcMacroKind::Derive,
+            ExpandArgs::Bang { .. } => ProcMacroKind::Bang,
+        }
+    }
+
+    /// Splits into the `(item, args)` pair the FFI layer always sends, with the
+    /// unused side of a derive/bang call filled in as an empty token stream.
+    fn into_item_and_args(self) -> (TokenStream, TokenStream) {
+        match self {
+            ExpandArgs::Attribute { item, args } => (item, args),
+            ExpandArgs::Derive { item } => (item, TokenStream::new(String::new())),
+            ExpandArgs::Bang { args } => (TokenStream::new(String::new()), args),
+        }
+    }
+}
+
 /// Representation of a single procedural macro.
 ///
 /// This struct is a wrapper around a shared library containing the procedural macro implementation.
 /// It is responsible for loading the shared library and providing a safe interface for code expansion.
 pub struct ProcMacroInstance {
     package_id: PackageId,
-    plugin: Plugin,
+    execution: Execution,
+}
+
+/// How a macro library is actually run: loaded directly into this process, or
+/// expanded through a dedicated `scarb-proc-macro-srv` child process.
+///
+/// Out-of-process execution is opt-in (via `SCARB_PROC_MACRO_SERVER=1`) because it
+/// costs an extra process + IPC round trip per expansion, but it isolates the host
+/// from macro crashes without `dlopen`-ing the macro into the compiler. The child
+/// still validates the library against the host architecture, so a macro built for a
+/// different host is rejected in either mode.
+enum Execution {
+    InProcess(Plugin),
+    // `ProcMacroInstance`'s methods all take `&self` while `ProcMacroServerConnection`'s
+    // take `&mut self`, so interior mutability is needed, and a `Mutex` rather than a
+    // `RefCell`: expansion may be driven from more than one thread at a time (e.g. concurrently expanding
+    // macros across items or packages). `RefCell` would either block `Execution`
+    // from ever being `Sync`, or panic under concurrent access if that were forced.
+    OutOfProcess(std::sync::Mutex<ProcMacroServerConnection>),
 }
 
 impl Debug for ProcMacroInstance {
@@ -58,69 +152,251 @@ impl ProcMacroInstance {
     /// Load shared library
     pub fn try_new(package: Package, config: &Config) -> Result<Self> {
         let lib_path = package.shared_lib_path(config);
-        let plugin = unsafe { Plugin::try_new(lib_path.to_path_buf())? };
+        let execution = if std::env::var_os("SCARB_PROC_MACRO_SERVER").is_some() {
+            let server_bin = proc_macro_server_path()?;
+            let connection = ProcMacroServerConnection::spawn(server_bin, lib_path.to_path_buf())
+                .with_context(|| {
+                format!(
+                    "failed to start macro server for package `{}`",
+                    package.id.name
+                )
+            })?;
+            Execution::OutOfProcess(std::sync::Mutex::new(connection))
+        } else {
+            let plugin = unsafe { Plugin::try_new(lib_path.to_path_buf())? };
+            Execution::InProcess(plugin)
+        };
         Ok(Self {
-            plugin,
+            execution,
             package_id: package.id,
         })
     }
-    pub fn declared_attributes(&self) -> Vec<String> {
-        vec![self.package_id.name.to_string()]
+    /// Names and kinds of all macros this library declares.
+    ///
+    /// Falls back to a single attribute macro named after the package if the library
+    /// predates the `list_declared_macros` entrypoint, or does not implement it. This
+    /// fallback only applies when discovery legitimately isn't supported (the
+    /// in-process path returning `None`, or either path returning an empty list)
+    /// -- a failed `ListMacros` request (child crashed, IO error) is a
+    /// real error and is propagated rather than silently treated the same way,
+    /// otherwise the caller would see a confusingly-wrong macro name instead of the
+    /// crash that actually happened.
+    pub fn declared_macros(&self) -> Result<Vec<(String, ProcMacroKind)>> {
+        let macros = match &self.execution {
+            Execution::InProcess(plugin) => plugin.vtable.list_declared_macros(),
+            Execution::OutOfProcess(server) => Some(
+                server
+                    .lock()
+                    .expect("proc-macro server connection mutex poisoned")
+                    .list_macros(&self.package_id.name.to_string())
+                    .with_context(|| {
+                        format!(
+                            "failed to discover macros declared by package `{}`",
+                            self.package_id.name
+                        )
+                    })?,
+            ),
+        };
+        Ok(match macros {
+            Some(macros) if !macros.is_empty() => macros,
+            _ => vec![(self.package_id.name.to_string(), ProcMacroKind::Attribute)],
+        })
+    }
+
+    /// Names of the attribute macros this library declares.
+    pub fn declared_attributes(&self) -> Result<Vec<String>> {
+        Ok(self
+            .declared_macros()?
+            .into_iter()
+            .filter(|(_, kind)| *kind == ProcMacroKind::Attribute)
+            .map(|(name, _)| name)
+            .collect())
     }
 
     /// Apply expansion to token stream.
     ///
-    /// This function implements the actual calls to functions from the dynamic library.
+    /// When running in-process, this calls straight into the dynamic library. All
+    /// values passing that FFI-barrier must implement a stable ABI, and the memory
+    /// must be freed on the same side of the barrier where the allocation was made.
     ///
-    /// All values passing the FFI-barrier must implement a stable ABI.
+    /// The FFI entrypoints are declared `extern "C-unwind"` (see `ExpandCode` et al.)
+    /// rather than plain `extern "C"`, and the call is wrapped in
+    /// [`std::panic::catch_unwind`]. As of Rust 1.71, a panic unwinding out of a
+    /// plain `extern "C"` function aborts the process at that boundary before it ever
+    /// reaches a caller's `catch_unwind` — `catch_unwind` alone does *not* protect
+    /// against a macro panic. `"C-unwind"` tells the compiler unwinding may cross the
+    /// boundary, so a panic can propagate up to this frame and be caught here, naming
+    /// the offending package instead of aborting. This still requires the macro
+    /// library's own entrypoints to be compiled with the matching unwind-aware ABI
+    /// (which the companion `cairo_lang_macro` export helpers do); a library built
+    /// with a plain `extern "C" fn expand` will still abort on panic regardless of how
+    /// we declare the function pointer type here. Out-of-process mode (below) isolates
+    /// crashes unconditionally, independent of how the macro library was compiled, and
+    /// is the only way to get that guarantee for a library we don't control.
     ///
-    /// Please be aware that the memory management of values passing the FFI-barrier is tricky.
-    /// The memory must be freed on the same side of the barrier, where the allocation was made.
-    pub(crate) fn generate_code(&self, token_stream: TokenStream) -> ProcMacroResult {
-        // This must be manually freed with call to from_owned_stable.
-        let stable_token_stream = token_stream.into_stable();
-        // Call FFI interface for code expansion.
-        // Note that `stable_result` has been allocated by the dynamic library.
-        let stable_result = (self.plugin.vtable.expand)(stable_token_stream);
-        // Free the memory allocated by the `stable_token_stream`.
-        // This will call `CString::from_raw` under the hood, to take ownership.
-        unsafe {
-            TokenStream::from_owned_stable(stable_result.input);
-        };
-        // Create Rust representation of the result.
-        // Note, that the memory still needs to be freed on the allocator side!
-        let result = unsafe { ProcMacroResult::from_stable(&stable_result.output) };
-        // Call FFI interface to free the `stable_result` that has been allocated by previous call.
-        (self.plugin.vtable.free_result)(stable_result.output);
-        // Return obtained result.
-        result
+    /// When running out-of-process, the token stream and result are instead sent over
+    /// the wire to the macro's dedicated server process; a crash there is reported as
+    /// an error naming this package rather than taking the whole build down.
+    pub(crate) fn generate_code(
+        &self,
+        macro_name: &str,
+        args: ExpandArgs,
+    ) -> Result<ProcMacroResult> {
+        let kind = args.kind();
+        let (item, attr_args) = args.into_item_and_args();
+        match &self.execution {
+            Execution::InProcess(plugin) => {
+                let macro_name_c =
+                    CString::new(macro_name).context("macro name must not contain a NUL byte")?;
+                std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                    // These must be manually freed with calls to from_owned_stable.
+                    let stable_item = item.into_stable();
+                    let stable_args = attr_args.into_stable();
+                    // Call FFI interface for code expansion.
+                    // Note that `stable_result` has been allocated by the dynamic library.
+                    let stable_result =
+                        plugin
+                            .vtable
+                            .expand(kind, &macro_name_c, stable_item, stable_args);
+                    // Free the memory allocated by the input token streams.
+                    // This will call `CString::from_raw` under the hood, to take ownership.
+                    unsafe {
+                        TokenStream::from_owned_stable(stable_result.input);
+                    };
+                    // Create Rust representation of the result.
+                    // Note, that the memory still needs to be freed on the allocator side!
+                    let result = unsafe { ProcMacroResult::from_stable(&stable_result.output) };
+                    // Call FFI interface to free the `stable_result` that has been allocated by previous call.
+                    plugin.vtable.free_result(stable_result.output);
+                    // Return obtained result.
+                    result
+                }))
+                .map_err(|payload| {
+                    self.panic_to_error(macro_name_c.to_string_lossy().as_ref(), payload)
+                })
+            }
+            Execution::OutOfProcess(server) => server
+                .lock()
+                .expect("proc-macro server connection mutex poisoned")
+                .expand_code(
+                    &self.package_id.name.to_string(),
+                    macro_name,
+                    kind,
+                    item,
+                    attr_args,
+                ),
+        }
+    }
+
+    /// Turns a caught FFI panic payload into a diagnostic-friendly error naming this
+    /// package and macro, matching how the out-of-process path reports a crashed child.
+    fn panic_to_error(
+        &self,
+        macro_name: &str,
+        payload: Box<dyn std::any::Any + Send>,
+    ) -> anyhow::Error {
+        let message = payload
+            .downcast_ref::<&str>()
+            .map(|s| s.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "<non-string panic payload>".to_string());
+        anyhow::anyhow!(
+            "procedural macro `{macro_name}` (from package `{}`) panicked while expanding code: {message}",
+            self.package_id.name
+        )
     }
 
-    pub(crate) fn aux_data_callback(&self, aux_data: Vec<ProcMacroAuxData>) {
-        // Convert to stable aux data.
+    pub(crate) fn aux_data_callback(&self, aux_data: Vec<ProcMacroAuxData>) -> Result<()> {
         let aux_data: Vec<AuxData> = aux_data.into_iter().map(Into::into).collect();
-        let aux_data = aux_data
-            .into_iter()
-            .map(|a| a.into_stable())
-            .collect::<Vec<_>>();
-        // Create stable slice representation from vector.
-        // Note this needs to be freed manually.
-        let aux_data = StableSlice::new(aux_data);
-        // Actual call to FFI interface for aux data callback.
-        let aux_data = (self.plugin.vtable.aux_data_callback)(aux_data);
-        // Free the memory allocated by vec.
-        let _ = aux_data.into_owned();
+        match &self.execution {
+            Execution::InProcess(plugin) => {
+                let aux_data = aux_data
+                    .into_iter()
+                    .map(|a| a.into_stable())
+                    .collect::<Vec<_>>();
+                // Create stable slice representation from vector.
+                // Note this needs to be freed manually.
+                let aux_data = StableSlice::new(aux_data);
+                // Actual call to FFI interface for aux data callback.
+                let aux_data = plugin.vtable.aux_data_callback(aux_data);
+                // Free the memory allocated by vec.
+                let _ = aux_data.into_owned();
+            }
+            Execution::OutOfProcess(server) => {
+                server
+                    .lock()
+                    .expect("proc-macro server connection mutex poisoned")
+                    .aux_data_callback(&self.package_id.name.to_string(), aux_data)?;
+            }
+        }
+        Ok(())
     }
 }
 
-type ExpandCode = extern "C" fn(StableTokenStream) -> StableResultWrapper;
-type FreeResult = extern "C" fn(StableProcMacroResult);
-type AuxDataCallback = extern "C" fn(StableSlice<StableAuxData>) -> StableSlice<StableAuxData>;
+/// Locates the `scarb-proc-macro-srv` helper binary, which Scarb ships alongside
+/// its own executable.
+fn proc_macro_server_path() -> Result<Utf8PathBuf> {
+    let current_exe =
+        std::env::current_exe().context("failed to resolve path to the current executable")?;
+    let dir = current_exe
+        .parent()
+        .context("current executable has no parent directory")?;
+    let file_name = if cfg!(windows) {
+        "scarb-proc-macro-srv.exe"
+    } else {
+        "scarb-proc-macro-srv"
+    };
+    Utf8PathBuf::from_path_buf(dir.join(file_name))
+        .map_err(|path| anyhow::anyhow!("executable path `{}` is not valid UTF-8", path.display()))
+}
+
+/// `(kind, macro_name, item, attribute_args) -> result`. Which of `item` /
+/// `attribute_args` is populated depends on `kind`: see [`ProcMacroKind`].
+///
+/// Declared `"C-unwind"` rather than plain `"C"` so that a panic inside the macro
+/// library is allowed to unwind across this boundary instead of aborting the process
+/// the instant it tries to. This only has an effect if the macro library's own
+/// `expand` is compiled against the same unwind-aware ABI; see the doc comment on
+/// [`ProcMacroInstance::generate_code`].
+type ExpandCode = extern "C-unwind" fn(
+    u8,
+    *const std::os::raw::c_char,
+    StableTokenStream,
+    StableTokenStream,
+) -> StableResultWrapper;
+type FreeResult = extern "C-unwind" fn(StableProcMacroResult);
+type AuxDataCallback =
+    extern "C-unwind" fn(StableSlice<StableAuxData>) -> StableSlice<StableAuxData>;
+type AbiVersion = extern "C-unwind" fn() -> u8;
+type ListDeclaredMacros = extern "C-unwind" fn() -> StableSlice<StableMacroDeclaration>;
+
+/// A single `(name, kind)` pair as returned by a library's `list_declared_macros`
+/// entrypoint. `kind` is a [`ProcMacroKind`] discriminant, see [`ProcMacroKind::as_u8`].
+#[repr(C)]
+struct StableMacroDeclaration {
+    name: *mut std::os::raw::c_char,
+    kind: u8,
+}
+
+/// Name of the symbol that a macro library must export so Scarb can tell which
+/// FFI shape (`VTableV0`, `VTableV1`, ...) it was built against.
+///
+/// Libraries that predate this negotiation do not export the symbol at all, in
+/// which case we assume the original, unversioned ABI (`v0`).
+const ABI_VERSION_SYMBOL: &[u8] = b"scarb_proc_macro_abi_version\0";
+
+/// Highest ABI version this build of Scarb knows how to speak.
+const SUPPORTED_ABI_VERSION: u8 = 0;
 
+/// Version 0 of the FFI vtable: the original, unversioned `expand` / `free_result` /
+/// `aux_data_callback` trio.
 struct VTableV0 {
     expand: RawSymbol<ExpandCode>,
     free_result: RawSymbol<FreeResult>,
     aux_data_callback: RawSymbol<AuxDataCallback>,
+    /// Optional: libraries predating multi-macro discovery do not export this, and
+    /// are treated as declaring a single macro named after their package.
+    list_declared_macros: Option<RawSymbol<ListDeclaredMacros>>,
 }
 
 impl VTableV0 {
@@ -137,25 +413,298 @@ impl VTableV0 {
             .get(b"aux_data_callback\0")
             .context("failed to load aux_data_callback function for procedural macro")?;
         let aux_data_callback = aux_data_callback.into_raw();
+        let list_declared_macros: Option<Symbol<'_, ListDeclaredMacros>> =
+            library.get(b"list_declared_macros\0").ok();
+        let list_declared_macros = list_declared_macros.map(|symbol| symbol.into_raw());
         Ok(VTableV0 {
             expand,
             free_result,
             aux_data_callback,
+            list_declared_macros,
         })
     }
+
+    fn expand(
+        &self,
+        kind: ProcMacroKind,
+        macro_name: &CStr,
+        item: StableTokenStream,
+        attribute_args: StableTokenStream,
+    ) -> StableResultWrapper {
+        (self.expand)(kind.as_u8(), macro_name.as_ptr(), item, attribute_args)
+    }
+
+    fn free_result(&self, result: StableProcMacroResult) {
+        (self.free_result)(result)
+    }
+
+    fn aux_data_callback(
+        &self,
+        aux_data: StableSlice<StableAuxData>,
+    ) -> StableSlice<StableAuxData> {
+        (self.aux_data_callback)(aux_data)
+    }
+
+    fn list_declared_macros(&self) -> Option<Vec<(String, ProcMacroKind)>> {
+        let list_declared_macros = self.list_declared_macros.as_ref()?;
+        let declarations = list_declared_macros();
+        let declarations = declarations
+            .into_owned()
+            .into_iter()
+            .filter_map(|declaration| {
+                let name = unsafe { CString::from_raw(declaration.name) }
+                    .to_string_lossy()
+                    .into_owned();
+                let kind = ProcMacroKind::from_u8(declaration.kind).ok()?;
+                Some((name, kind))
+            })
+            .collect();
+        Some(declarations)
+    }
+}
+
+/// The set of FFI entrypoints exposed by a loaded macro library, picked at load time
+/// based on the ABI version the library advertises.
+///
+/// New ABI versions should be added as new variants here, keeping old ones around so
+/// that macros compiled against an older Scarb keep working without a rebuild.
+enum VTable {
+    V0(VTableV0),
+}
+
+impl VTable {
+    /// Reads the `scarb_proc_macro_abi_version` symbol (if present) and builds the
+    /// vtable matching the version it advertises.
+    unsafe fn try_new(library: &Library) -> Result<VTable> {
+        let abi_version = match library.get::<AbiVersion>(ABI_VERSION_SYMBOL) {
+            Ok(abi_version) => abi_version(),
+            Err(_) => 0,
+        };
+        match abi_version {
+            0 => Ok(VTable::V0(VTableV0::try_new(library)?)),
+            other => bail!(
+                "macro built against incompatible Scarb ABI v{other}, this Scarb supports v{SUPPORTED_ABI_VERSION}\n\
+                 help: update Scarb, or rebuild the macro against this Scarb version"
+            ),
+        }
+    }
+
+    fn expand(
+        &self,
+        kind: ProcMacroKind,
+        macro_name: &CStr,
+        item: StableTokenStream,
+        attribute_args: StableTokenStream,
+    ) -> StableResultWrapper {
+        match self {
+            VTable::V0(vtable) => vtable.expand(kind, macro_name, item, attribute_args),
+        }
+    }
+
+    fn free_result(&self, result: StableProcMacroResult) {
+        match self {
+            VTable::V0(vtable) => vtable.free_result(result),
+        }
+    }
+
+    fn aux_data_callback(
+        &self,
+        aux_data: StableSlice<StableAuxData>,
+    ) -> StableSlice<StableAuxData> {
+        match self {
+            VTable::V0(vtable) => vtable.aux_data_callback(aux_data),
+        }
+    }
+
+    fn list_declared_macros(&self) -> Option<Vec<(String, ProcMacroKind)>> {
+        match self {
+            VTable::V0(vtable) => vtable.list_declared_macros(),
+        }
+    }
 }
 
 struct Plugin {
     #[allow(dead_code)]
     library: Library,
-    vtable: VTableV0,
+    vtable: VTable,
 }
 
 impl Plugin {
     unsafe fn try_new(library_path: Utf8PathBuf) -> Result<Plugin> {
-        let library = Library::new(library_path)?;
-        let vtable = VTableV0::try_new(&library)?;
+        validate_library(&library_path)?;
+        let library = Self::open_library(&library_path)?;
+        let vtable = VTable::try_new(&library)?;
 
         Ok(Plugin { library, vtable })
     }
+
+    /// Opens the macro library.
+    ///
+    /// On Linux, we load it with `RTLD_NOW | RTLD_DEEPBIND` so that the library
+    /// prefers its own copies of symbols (e.g. from a differently-versioned libstd)
+    /// over ones already loaded into the host process. Without `RTLD_DEEPBIND`, a
+    /// panic inside the macro can end up unwinding through the host's panic runtime
+    /// instead of its own, which is the documented "panic inside panic" hazard when
+    /// mixing differently-linked Rust runtimes across an FFI boundary.
+    ///
+    /// `RTLD_DEEPBIND` is a glibc extension; it does not exist on macOS, where bit
+    /// `0x8` is instead `RTLD_GLOBAL` (export the library's symbols process-wide).
+    /// Applying the Linux flag value there would do the opposite of what we want, so
+    /// on macOS we load with plain `RTLD_NOW`: Apple's two-level-namespace dyld already
+    /// binds each image's own symbol references to itself by default, which gives us
+    /// most of the isolation `RTLD_DEEPBIND` buys on Linux without needing the flag.
+    #[cfg(target_os = "linux")]
+    unsafe fn open_library(library_path: &Utf8PathBuf) -> Result<Library> {
+        use libloading::os::unix::Library as UnixLibrary;
+        const RTLD_NOW: i32 = 0x2;
+        const RTLD_DEEPBIND: i32 = 0x0008;
+        UnixLibrary::open(Some(library_path), RTLD_NOW | RTLD_DEEPBIND)
+            .map(Library::from)
+            .context("failed to load procedural macro shared library")
+    }
+
+    #[cfg(all(unix, not(target_os = "linux")))]
+    unsafe fn open_library(library_path: &Utf8PathBuf) -> Result<Library> {
+        use libloading::os::unix::Library as UnixLibrary;
+        const RTLD_NOW: i32 = 0x2;
+        UnixLibrary::open(Some(library_path), RTLD_NOW)
+            .map(Library::from)
+            .context("failed to load procedural macro shared library")
+    }
+
+    #[cfg(windows)]
+    unsafe fn open_library(library_path: &Utf8PathBuf) -> Result<Library> {
+        Library::new(library_path).context("failed to load procedural macro shared library")
+    }
+}
+
+/// Entrypoint for the `scarb-proc-macro-srv` helper binary: loads `library_path` in
+/// this (disposable) process and serves [`crate::compiler::plugin::proc_macro::srv`]
+/// requests over stdin/stdout until the host closes the pipe.
+///
+/// This is what makes out-of-process expansion possible: the host process never
+/// `dlopen`s the macro itself, so a crash while expanding stays contained here.
+///
+/// Before entering the request loop, a [`Handshake`] is sent reporting whether
+/// `library_path` was loaded successfully: [`ProcMacroServerConnection::spawn`]
+/// waits for this so a load failure is reported to the host with its real error
+/// message, instead of only surfacing once the first request gets no response.
+pub fn serve(library_path: Utf8PathBuf) -> Result<()> {
+    use crate::compiler::plugin::proc_macro::srv::{write_handshake, Handshake, Request, Response};
+    use std::io::{self, Read, Write};
+
+    let stdin = io::stdin();
+    let stdout = io::stdout();
+    let mut stdin = stdin.lock();
+    let mut stdout = stdout.lock();
+
+    let plugin = match unsafe { Plugin::try_new(library_path) } {
+        Ok(plugin) => {
+            write_handshake(&mut stdout, &Handshake::Ready)?;
+            plugin
+        }
+        Err(err) => {
+            write_handshake(&mut stdout, &Handshake::Err(format!("{err:#}")))?;
+            return Err(err);
+        }
+    };
+
+    loop {
+        let mut len_buf = [0u8; 4];
+        if let Err(err) = stdin.read_exact(&mut len_buf) {
+            if err.kind() == io::ErrorKind::UnexpectedEof {
+                return Ok(());
+            }
+            return Err(err.into());
+        }
+        let len = u32::from_le_bytes(len_buf) as usize;
+        let mut buf = vec![0u8; len];
+        stdin.read_exact(&mut buf)?;
+        let request: Request =
+            serde_json::from_slice(&buf).context("failed to deserialize proc-macro request")?;
+
+        let response = match request {
+            Request::ExpandCode {
+                macro_name,
+                kind,
+                item,
+                args,
+            } => {
+                let macro_name =
+                    CString::new(macro_name).context("macro name must not contain a NUL byte")?;
+                let stable_item = item.into_stable();
+                let stable_args = args.into_stable();
+                let stable_result =
+                    plugin
+                        .vtable
+                        .expand(kind, &macro_name, stable_item, stable_args);
+                unsafe {
+                    TokenStream::from_owned_stable(stable_result.input);
+                };
+                let result = unsafe { ProcMacroResult::from_stable(&stable_result.output) };
+                plugin.vtable.free_result(stable_result.output);
+                Response::ExpandCode(result)
+            }
+            Request::AuxDataCallback { aux_data } => {
+                let stable_aux_data = aux_data.into_iter().map(|a| a.into_stable()).collect();
+                let stable_aux_data = StableSlice::new(stable_aux_data);
+                // The callback's return value mirrors the in-process implementation,
+                // which also discards it once the library has observed the aux data.
+                let stable_aux_data = plugin.vtable.aux_data_callback(stable_aux_data);
+                let _ = stable_aux_data.into_owned();
+                Response::AuxDataCallback(Vec::new())
+            }
+            Request::ListMacros => {
+                Response::ListMacros(plugin.vtable.list_declared_macros().unwrap_or_default())
+            }
+        };
+
+        let bytes =
+            serde_json::to_vec(&response).context("failed to serialize proc-macro response")?;
+        stdout.write_all(&(bytes.len() as u32).to_le_bytes())?;
+        stdout.write_all(&bytes)?;
+        stdout.flush()?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn proc_macro_kind_round_trips_through_its_discriminant() {
+        for kind in [
+            ProcMacroKind::Attribute,
+            ProcMacroKind::Derive,
+            ProcMacroKind::Bang,
+        ] {
+            assert_eq!(ProcMacroKind::from_u8(kind.as_u8()).unwrap(), kind);
+        }
+    }
+
+    #[test]
+    fn proc_macro_kind_rejects_unknown_discriminant() {
+        assert!(ProcMacroKind::from_u8(3).is_err());
+    }
+
+    #[test]
+    fn expand_args_kind_matches_its_own_variant() {
+        let empty = || TokenStream::new(String::new());
+        assert_eq!(
+            ExpandArgs::Attribute {
+                item: empty(),
+                args: empty()
+            }
+            .kind(),
+            ProcMacroKind::Attribute
+        );
+        assert_eq!(
+            ExpandArgs::Derive { item: empty() }.kind(),
+            ProcMacroKind::Derive
+        );
+        assert_eq!(
+            ExpandArgs::Bang { args: empty() }.kind(),
+            ProcMacroKind::Bang
+        );
+    }
 }
@@ -0,0 +1,212 @@
+//! Wire protocol and client for out-of-process procedural macro expansion.
+//!
+//! Instead of `dlopen`-ing a macro library directly in the `scarb` process, a package
+//! can be expanded by a short-lived `scarb-proc-macro-srv` child process: the child
+//! loads the library and speaks a small length-delimited, newline-free JSON protocol
+//! over its stdin/stdout. If the macro crashes (segfault, abort, stack overflow), only
+//! the child dies; the host process observes this as a regular, recoverable error
+//! instead of taking the whole `scarb build` down with it.
+//!
+//! This mirrors the design of rust-analyzer's `proc-macro-srv`.
+
+use std::io::{BufReader, Read, Write};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+
+use anyhow::{bail, Context, Result};
+use cairo_lang_macro::{AuxData, ProcMacroResult, TokenStream};
+use camino::Utf8PathBuf;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::compiler::plugin::proc_macro::ffi::ProcMacroKind;
+
+/// The first message a `scarb-proc-macro-srv` child sends, before the regular
+/// request/response loop begins: whether it managed to load its macro library.
+///
+/// Without this, a library that fails [`validate_library`](crate::compiler::plugin::proc_macro::validation::validate_library)
+/// or ABI negotiation inside the child only surfaces once the host makes its first
+/// `Request`, as an opaque "macro server process exited with ..." -- the precise
+/// error `Plugin::try_new` produced is lost along with the child. Sending it up front
+/// instead lets `ProcMacroServerConnection::spawn` fail with that exact message.
+#[derive(Serialize, Deserialize, Debug)]
+pub enum Handshake {
+    Ready,
+    Err(String),
+}
+
+/// A request sent from the host process to a `scarb-proc-macro-srv` child.
+#[derive(Serialize, Deserialize, Debug)]
+pub enum Request {
+    /// Expand a single invocation of the named macro. Which of `item` / `args` is
+    /// populated depends on `kind`, see [`ProcMacroKind`].
+    ExpandCode {
+        macro_name: String,
+        kind: ProcMacroKind,
+        item: TokenStream,
+        args: TokenStream,
+    },
+    /// Forward aux data collected so far to the macro's aux data callback.
+    AuxDataCallback { aux_data: Vec<AuxData> },
+    /// List the macros (name and kind) this library implements.
+    ListMacros,
+}
+
+impl Request {
+    /// Short, human-readable description of what this request does, used to name
+    /// what was happening when a crash is reported.
+    fn describe(&self) -> &'static str {
+        match self {
+            Request::ExpandCode { .. } => "expanding code",
+            Request::AuxDataCallback { .. } => "reporting aux data",
+            Request::ListMacros => "discovering declared macros",
+        }
+    }
+}
+
+/// The response matching a [`Request`] sent to a `scarb-proc-macro-srv` child.
+#[derive(Serialize, Deserialize, Debug)]
+pub enum Response {
+    ExpandCode(ProcMacroResult),
+    AuxDataCallback(Vec<AuxData>),
+    ListMacros(Vec<(String, ProcMacroKind)>),
+}
+
+fn write_message(writer: &mut impl Write, message: &impl Serialize) -> Result<()> {
+    let bytes = serde_json::to_vec(message).context("failed to serialize proc-macro message")?;
+    writer.write_all(&(bytes.len() as u32).to_le_bytes())?;
+    writer.write_all(&bytes)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Sends the initial [`Handshake`] a `scarb-proc-macro-srv` child must write before
+/// entering its request loop; see [`ProcMacroServerConnection::spawn`].
+pub(crate) fn write_handshake(writer: &mut impl Write, handshake: &Handshake) -> Result<()> {
+    write_message(writer, handshake)
+}
+
+fn read_message<T: DeserializeOwned>(reader: &mut impl Read) -> Result<Option<T>> {
+    let mut len_buf = [0u8; 4];
+    if let Err(err) = reader.read_exact(&mut len_buf) {
+        if err.kind() == std::io::ErrorKind::UnexpectedEof {
+            return Ok(None);
+        }
+        return Err(err.into());
+    }
+    let len = u32::from_le_bytes(len_buf) as usize;
+    let mut buf = vec![0u8; len];
+    reader.read_exact(&mut buf)?;
+    let message =
+        serde_json::from_slice(&buf).context("failed to deserialize proc-macro response")?;
+    Ok(Some(message))
+}
+
+/// A connection to a single, dedicated `scarb-proc-macro-srv` child process hosting
+/// one macro library.
+pub struct ProcMacroServerConnection {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+}
+
+impl ProcMacroServerConnection {
+    /// Spawns the server binary, has it load `library_path`, and waits for its initial
+    /// [`Handshake`] before returning: a library that fails to load is reported here
+    /// with the child's actual error message, rather than surfacing later as an
+    /// opaque "process exited with ..." on the first real request.
+    pub fn spawn(server_bin: Utf8PathBuf, library_path: Utf8PathBuf) -> Result<Self> {
+        let mut child = Command::new(server_bin.as_str())
+            .arg(library_path.as_str())
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .context("failed to spawn proc-macro server process")?;
+        let stdin = child.stdin.take().expect("stdin must be piped");
+        let mut stdout = BufReader::new(child.stdout.take().expect("stdout must be piped"));
+
+        match read_message::<Handshake>(&mut stdout)? {
+            Some(Handshake::Ready) => {}
+            Some(Handshake::Err(message)) => {
+                let _ = child.wait();
+                bail!("failed to load procedural macro library `{library_path}`: {message}");
+            }
+            None => {
+                let status = child
+                    .wait()
+                    .context("failed to read proc-macro server exit status")?;
+                bail!(
+                    "procedural macro server for `{library_path}` exited before sending its handshake: {status}"
+                )
+            }
+        }
+
+        Ok(Self {
+            child,
+            stdin,
+            stdout,
+        })
+    }
+
+    fn request(&mut self, package_name: &str, request: Request) -> Result<Response> {
+        let description = request.describe();
+        write_message(&mut self.stdin, &request)
+            .with_context(|| format!("failed to send request to `{package_name}` macro server"))?;
+        match read_message(&mut self.stdout)? {
+            Some(response) => Ok(response),
+            None => {
+                let status = self
+                    .child
+                    .wait()
+                    .context("failed to read proc-macro server exit status")?;
+                bail!(
+                    "procedural macro `{package_name}` crashed while {description}: \
+                     macro server process exited with {status}"
+                )
+            }
+        }
+    }
+
+    pub fn expand_code(
+        &mut self,
+        package_name: &str,
+        macro_name: &str,
+        kind: ProcMacroKind,
+        item: TokenStream,
+        args: TokenStream,
+    ) -> Result<ProcMacroResult> {
+        let request = Request::ExpandCode {
+            macro_name: macro_name.to_string(),
+            kind,
+            item,
+            args,
+        };
+        match self.request(package_name, request)? {
+            Response::ExpandCode(result) => Ok(result),
+            other => bail!("unexpected response to ExpandCode request: {other:?}"),
+        }
+    }
+
+    pub fn aux_data_callback(
+        &mut self,
+        package_name: &str,
+        aux_data: Vec<AuxData>,
+    ) -> Result<Vec<AuxData>> {
+        match self.request(package_name, Request::AuxDataCallback { aux_data })? {
+            Response::AuxDataCallback(aux_data) => Ok(aux_data),
+            other => bail!("unexpected response to AuxDataCallback request: {other:?}"),
+        }
+    }
+
+    pub fn list_macros(&mut self, package_name: &str) -> Result<Vec<(String, ProcMacroKind)>> {
+        match self.request(package_name, Request::ListMacros)? {
+            Response::ListMacros(names) => Ok(names),
+            other => bail!("unexpected response to ListMacros request: {other:?}"),
+        }
+    }
+}
+
+impl Drop for ProcMacroServerConnection {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
@@ -0,0 +1,172 @@
+//! Pre-`dlopen` validation of procedural macro shared libraries.
+//!
+//! Loading a wrong-target or stripped library straight through the system loader
+//! produces an unhelpful error ("image not found", "wrong ELF class", ...). Instead,
+//! `mmap` the file and parse its object format with the `object` crate (the same
+//! approach rust-analyzer's `dylib.rs` takes with `memmap2` + `object`) so we can
+//! point at exactly what is wrong before handing the library to `libloading`.
+
+use anyhow::{bail, Context, Result};
+use camino::Utf8Path;
+use object::Object;
+use std::collections::HashSet;
+use std::fs::File;
+
+/// Symbols every macro library must export, regardless of the ABI version it
+/// advertises: `ffi::VTableV0`, the only vtable `ffi::VTable::try_new` can build,
+/// loads all three unconditionally.
+const REQUIRED_SYMBOLS: &[&str] = &["expand", "free_result", "aux_data_callback"];
+
+/// Checks that `library_path` is a shared library built for the host architecture and
+/// exporting every symbol Scarb requires, before we attempt to `dlopen` it.
+pub fn validate_library(library_path: &Utf8Path) -> Result<()> {
+    let file = File::open(library_path)
+        .with_context(|| format!("failed to open procedural macro library `{library_path}`"))?;
+    // Safety: we only read from the mapping, and the file is not expected to be
+    // mutated by another process while Scarb is running.
+    let mmap = unsafe { memmap2::Mmap::map(&file) }
+        .with_context(|| format!("failed to mmap procedural macro library `{library_path}`"))?;
+    let object = object::File::parse(&*mmap)
+        .with_context(|| format!("`{library_path}` is not a valid shared library"))?;
+
+    let host_arch = host_architecture();
+    if object.architecture() != host_arch {
+        bail!(
+            "procedural macro library `{library_path}` was built for {:?}, host is {:?}",
+            object.architecture(),
+            host_arch
+        );
+    }
+
+    // `exports()` is the format-agnostic API and has a real PE implementation, unlike
+    // `dynamic_symbols()`: `object`'s COFF/PE backends always return an empty dynamic
+    // symbol iterator (exports there only show up via the PE export directory), so
+    // relying on `dynamic_symbols()` alone would reject every valid Windows `.dll`.
+    let exported_names: HashSet<String> = object
+        .exports()
+        .with_context(|| format!("failed to read exports of `{library_path}`"))?
+        .into_iter()
+        .filter_map(|export| std::str::from_utf8(export.name()).ok().map(str::to_string))
+        .map(|name| strip_macos_underscore(&name))
+        .collect();
+
+    for symbol in REQUIRED_SYMBOLS {
+        if !exported_names.contains(*symbol) {
+            bail!(
+                "procedural macro library `{library_path}` is missing required symbol `{symbol}`"
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// On macOS, exported C symbols carry a leading underscore in the object file's
+/// symbol table (`_expand`) that is not part of the name passed to `dlsym`/
+/// `libloading::Library::get` (`expand`). Strip it so symbol-presence checks here
+/// match what the subsequent load will actually request.
+fn strip_macos_underscore(name: &str) -> String {
+    if cfg!(target_os = "macos") {
+        name.strip_prefix('_').unwrap_or(name).to_string()
+    } else {
+        name.to_string()
+    }
+}
+
+fn host_architecture() -> object::Architecture {
+    if cfg!(target_arch = "x86_64") {
+        object::Architecture::X86_64
+    } else if cfg!(target_arch = "aarch64") {
+        object::Architecture::Aarch64
+    } else if cfg!(target_arch = "x86") {
+        object::Architecture::I386
+    } else {
+        object::Architecture::Unknown
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use camino::Utf8PathBuf;
+    use object::write::{Object as WriteObject, Symbol, SymbolSection};
+    use object::{BinaryFormat, Endianness, SymbolFlags, SymbolKind, SymbolScope};
+
+    /// Builds a minimal relocatable ELF object file defining `exported_symbols` as
+    /// global symbols, written for `architecture`, and returns the path it was
+    /// written to.
+    fn write_fixture_library(
+        architecture: object::Architecture,
+        exported_symbols: &[&str],
+    ) -> Utf8PathBuf {
+        let mut object = WriteObject::new(BinaryFormat::Elf, architecture, Endianness::Little);
+        for name in exported_symbols {
+            object.add_symbol(Symbol {
+                name: name.as_bytes().to_vec(),
+                value: 0,
+                size: 0,
+                kind: SymbolKind::Text,
+                scope: SymbolScope::Dynamic,
+                weak: false,
+                section: SymbolSection::Absolute,
+                flags: SymbolFlags::None,
+            });
+        }
+        let bytes = object.write().expect("fixture object must serialize");
+
+        let path = std::env::temp_dir().join(format!(
+            "scarb-validate-library-fixture-{:?}-{}.so",
+            architecture,
+            exported_symbols.join("-")
+        ));
+        std::fs::write(&path, bytes).expect("failed to write fixture library");
+        Utf8PathBuf::from_path_buf(path).expect("fixture path must be UTF-8")
+    }
+
+    #[test]
+    fn rejects_library_built_for_a_different_architecture() {
+        let foreign_arch = if cfg!(target_arch = "x86_64") {
+            object::Architecture::Aarch64
+        } else {
+            object::Architecture::X86_64
+        };
+        let path = write_fixture_library(foreign_arch, REQUIRED_SYMBOLS);
+
+        let err = validate_library(&path).unwrap_err();
+        assert!(err.to_string().contains("was built for"));
+
+        std::fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn rejects_library_missing_a_required_symbol() {
+        let path = write_fixture_library(host_architecture(), &["expand", "free_result"]);
+
+        let err = validate_library(&path).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("missing required symbol `aux_data_callback`"));
+
+        std::fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn accepts_library_exporting_all_required_symbols() {
+        let path = write_fixture_library(host_architecture(), REQUIRED_SYMBOLS);
+
+        validate_library(&path).expect("library exporting all required symbols should validate");
+
+        std::fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn strips_leading_underscore_only_on_macos() {
+        let stripped = strip_macos_underscore("_expand");
+        if cfg!(target_os = "macos") {
+            assert_eq!(stripped, "expand");
+        } else {
+            assert_eq!(stripped, "_expand");
+        }
+        assert_eq!(strip_macos_underscore("expand"), "expand");
+    }
+}